@@ -1,23 +1,203 @@
 //! Local Exceptions.
 
 use std::{io, fs, thread};
+use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::time::{Duration, SystemTime};
 use arc_swap::ArcSwap;
+use base64::Engine;
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
 use futures::future::{select, Either, FutureExt};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use rpki::slurm::{SlurmFile, ValidationOutputFilters};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::Error as _;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
 use crate::payload;
 use crate::comms::{Gate, Link, Terminated, UnitStatus};
 use crate::config::ConfigPath;
 use crate::manager::Component;
+use crate::metrics::{self, Metric, MetricType, MetricUnit};
 
 
 //------------ Configuration -------------------------------------------------
 
-/// How long should the update thread sleep before checking files again?
-const UPDATE_SLEEP: Duration = Duration::from_secs(2);
+/// How long to wait between fallback sweeps of all files.
+///
+/// The update thread is primarily event-driven -- woken by filesystem
+/// notifications or SIGHUP -- but atomic rename/replace can confuse a
+/// watcher, and remote files have no filesystem events at all. This sweep
+/// catches anything the event-driven path missed.
+const FALLBACK_SLEEP: Duration = Duration::from_secs(60);
+
+/// How long to wait for a remote SLURM file to respond.
+///
+/// The update thread is shared by every configured file, so a slow or
+/// unresponsive remote server must not be allowed to stall local files and
+/// other remote files behind it indefinitely.
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+//------------ FileConfig -----------------------------------------------------
+
+/// A single configured SLURM file.
+///
+/// A file can be given as a plain path, as a path together with an
+/// [`EncryptionConfig`] if the file is stored encrypted at rest, or as a
+/// URL to fetch it from over HTTP(S).
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FileConfig {
+    /// A plain, unencrypted local SLURM file.
+    Plain(ConfigPath),
+
+    /// A local SLURM file that is encrypted at rest.
+    Encrypted {
+        /// The path to the encrypted file.
+        path: ConfigPath,
+
+        /// The parameters needed to decrypt it.
+        encryption: EncryptionConfig,
+    },
+
+    /// A SLURM file fetched periodically over HTTP(S).
+    Remote {
+        /// The URL to fetch the file from.
+        url: String,
+    },
+}
+
+impl FileConfig {
+    /// Returns the path of a local file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called on a [`FileConfig::Remote`].
+    fn path(&self) -> &Path {
+        match self {
+            FileConfig::Plain(path) => path.as_ref(),
+            FileConfig::Encrypted { path, .. } => path.as_ref(),
+            FileConfig::Remote { .. } => {
+                unreachable!("remote files have no local path")
+            }
+        }
+    }
+
+    /// Returns the encryption parameters of a local file, if any.
+    fn encryption(&self) -> Option<&EncryptionConfig> {
+        match self {
+            FileConfig::Plain(_) | FileConfig::Remote { .. } => None,
+            FileConfig::Encrypted { encryption, .. } => Some(encryption),
+        }
+    }
+
+    /// Returns a human-readable label identifying the file, for metrics.
+    fn label(&self) -> String {
+        match self {
+            FileConfig::Plain(path) | FileConfig::Encrypted { path, .. } => {
+                path.as_ref().display().to_string()
+            }
+            FileConfig::Remote { url } => url.clone(),
+        }
+    }
+}
+
+
+//------------ EncryptionConfig -----------------------------------------------
+
+/// The parameters for decrypting a SLURM file encrypted at rest.
+///
+/// Files are encrypted with the ChaCha20 stream cipher. The key can be
+/// given as either a hex or base64 string.
+///
+/// There is deliberately no nonce here: a key is expected to be reused
+/// across repeated re-encryptions of a file as its content changes over
+/// time (the whole point of the existing reload loop), and reusing a
+/// ChaCha20 key+nonce pair on different plaintext breaks confidentiality
+/// completely. Instead, every encrypted file carries its own nonce as a
+/// header, see [`DecryptReader::new`].
+#[derive(Debug, Deserialize)]
+struct EncryptionConfig {
+    /// The 32-byte ChaCha20 key.
+    #[serde(deserialize_with = "deserialize_key")]
+    key: [u8; 32],
+}
+
+/// Decodes a string as either hex or base64.
+fn decode_bytes<'de, D: Deserializer<'de>>(
+    s: &str
+) -> Result<Vec<u8>, D::Error> {
+    if let Ok(bytes) = hex::decode(s) {
+        return Ok(bytes)
+    }
+    base64::engine::general_purpose::STANDARD.decode(s).map_err(|err| {
+        D::Error::custom(format_args!(
+            "failed to decode as hex or base64: {}", err
+        ))
+    })
+}
+
+fn deserialize_key<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<[u8; 32], D::Error> {
+    let s = String::deserialize(deserializer)?;
+    let bytes = decode_bytes::<D>(&s)?;
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        D::Error::custom(format_args!(
+            "key must be 32 bytes, got {}", bytes.len()
+        ))
+    })
+}
+
+
+//------------ DecryptReader --------------------------------------------------
+
+/// The length in bytes of the per-file nonce header read by
+/// [`DecryptReader::new`].
+const NONCE_LEN: usize = 12;
+
+/// A `Read` adapter that decrypts a ChaCha20-encrypted stream on the fly.
+///
+/// The keystream is applied directly to each chunk as it is read, so the
+/// underlying ciphertext is never buffered in full.
+struct DecryptReader<R> {
+    /// The underlying reader producing ciphertext.
+    inner: R,
+
+    /// The cipher keystream, advanced by the bytes decrypted in each `read`.
+    cipher: ChaCha20,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// Wraps `inner`, reading its nonce from a header of `NONCE_LEN` bytes.
+    ///
+    /// The nonce lives in the file rather than in the configuration so
+    /// that the same configured key can be reused safely every time the
+    /// operator re-encrypts the file with new content: as long as each
+    /// re-encryption picks a fresh nonce for its header, the same
+    /// key+nonce pair is never applied to two different plaintexts.
+    fn new(mut inner: R, config: &EncryptionConfig) -> io::Result<Self> {
+        let mut nonce = [0u8; NONCE_LEN];
+        inner.read_exact(&mut nonce)?;
+        Ok(DecryptReader {
+            inner,
+            cipher: ChaCha20::new(&config.key.into(), &nonce.into()),
+        })
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.cipher.apply_keystream(&mut buf[..len]);
+        Ok(len)
+    }
+}
 
 
 //------------ LocalExceptions -----------------------------------------------
@@ -29,7 +209,21 @@ pub struct LocalExceptions {
     source: Link,
 
     /// A list of paths to the SLURM files.
-    files: Vec<ConfigPath>,
+    ///
+    /// An entry can also be a remote URL, see [`FileConfig`].
+    files: Vec<FileConfig>,
+
+    /// An optional path to persist the merged exception set to.
+    ///
+    /// If given, the most recently successfully-loaded content of all
+    /// files is written here so it can be used for a warm start on the
+    /// next restart, before the files themselves have been read again.
+    state_file: Option<ConfigPath>,
+
+    /// Whether to refuse a file whose content conflicts with an earlier
+    /// file, rather than just reporting the conflict.
+    #[serde(default)]
+    strict: bool,
 }
 
 impl LocalExceptions {
@@ -37,9 +231,15 @@ impl LocalExceptions {
         mut self, mut component: Component, mut gate: Gate
     ) -> Result<(), Terminated> {
         component.register_metrics(gate.metrics());
+        let labels: Vec<_> = self.files.iter().map(FileConfig::label).collect();
         let files = ExceptionSet::new(
-            self.files.into_iter().map(Into::into).collect()
+            self.files, self.state_file.map(Into::into), self.strict
         );
+        component.register_metrics(Arc::new(
+            LocalExceptionsMetrics::new(
+                labels, files.metrics(), files.conflict_count()
+            )
+        ));
         loop {
             let update = match select(
                 self.source.query().boxed(), gate.process().boxed()
@@ -54,6 +254,122 @@ impl LocalExceptions {
 }
 
 
+//------------ LocalExceptionsMetrics -----------------------------------------
+
+const METRIC_LAST_SUCCESS: Metric = Metric::new(
+    "local_exceptions_last_success",
+    "timestamp of the last successful load of this file",
+    MetricType::Gauge,
+    MetricUnit::Second,
+);
+const METRIC_ASSERTION_COUNT: Metric = Metric::new(
+    "local_exceptions_assertion_count",
+    "number of assertions in the currently loaded content of this file",
+    MetricType::Gauge,
+    MetricUnit::Total,
+);
+const METRIC_FILTER_COUNT: Metric = Metric::new(
+    "local_exceptions_filter_count",
+    "number of filters in the currently loaded content of this file",
+    MetricType::Gauge,
+    MetricUnit::Total,
+);
+const METRIC_DROPPED_COUNT: Metric = Metric::new(
+    "local_exceptions_dropped_count",
+    "total number of payloads dropped by filters in this file",
+    MetricType::Counter,
+    MetricUnit::Total,
+);
+const METRIC_INSERTED_COUNT: Metric = Metric::new(
+    "local_exceptions_inserted_count",
+    "total number of payloads inserted by assertions in this file",
+    MetricType::Counter,
+    MetricUnit::Total,
+);
+const METRIC_ERROR_COUNT: Metric = Metric::new(
+    "local_exceptions_error_count",
+    "total number of failed load attempts for this file",
+    MetricType::Counter,
+    MetricUnit::Total,
+);
+const METRIC_CONFLICT_COUNT: Metric = Metric::new(
+    "local_exceptions_conflict_count",
+    "number of cross-file conflicts found on the last validation",
+    MetricType::Gauge,
+    MetricUnit::Total,
+);
+const METRIC_LAST_ERROR_KIND: Metric = Metric::new(
+    "local_exceptions_last_error",
+    "info metric set to 1 with a label describing the most recent load \
+     error for this file; absent if the file has never failed to load",
+    MetricType::Gauge,
+    MetricUnit::Info,
+);
+
+/// The metrics for a [`LocalExceptions`] unit, one entry per configured file.
+struct LocalExceptionsMetrics {
+    files: Vec<(String, Arc<FileMetrics>)>,
+    conflict_count: Arc<AtomicU64>,
+}
+
+impl LocalExceptionsMetrics {
+    fn new(
+        labels: Vec<String>,
+        metrics: Arc<Vec<Arc<FileMetrics>>>,
+        conflict_count: Arc<AtomicU64>,
+    ) -> Self {
+        LocalExceptionsMetrics {
+            files: labels.into_iter().zip(metrics.iter().cloned()).collect(),
+            conflict_count,
+        }
+    }
+}
+
+impl metrics::Source for LocalExceptionsMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &METRIC_CONFLICT_COUNT, Some(unit_name), "",
+            self.conflict_count.load(Ordering::Relaxed)
+        );
+        for (label, metrics) in &self.files {
+            let last_success = metrics.last_success.lock().unwrap().and_then(
+                |time| time.duration_since(SystemTime::UNIX_EPOCH).ok()
+            );
+            target.append_simple(
+                &METRIC_LAST_SUCCESS, Some(unit_name), label,
+                last_success.map_or(-1., |duration| duration.as_secs() as f64)
+            );
+            target.append_simple(
+                &METRIC_ASSERTION_COUNT, Some(unit_name), label,
+                metrics.assertion_count.load(Ordering::Relaxed)
+            );
+            target.append_simple(
+                &METRIC_FILTER_COUNT, Some(unit_name), label,
+                metrics.filter_count.load(Ordering::Relaxed)
+            );
+            target.append_simple(
+                &METRIC_DROPPED_COUNT, Some(unit_name), label,
+                metrics.dropped_count.load(Ordering::Relaxed)
+            );
+            target.append_simple(
+                &METRIC_INSERTED_COUNT, Some(unit_name), label,
+                metrics.inserted_count.load(Ordering::Relaxed)
+            );
+            target.append_simple(
+                &METRIC_ERROR_COUNT, Some(unit_name), label,
+                metrics.error_count.load(Ordering::Relaxed)
+            );
+            if let Some(kind) = *metrics.last_error_kind.lock().unwrap() {
+                target.append_simple(
+                    &METRIC_LAST_ERROR_KIND, Some(unit_name),
+                    &format!("{label}, kind=\"{kind:?}\""), 1
+                );
+            }
+        }
+    }
+}
+
+
 //------------ ExceptionSet -------------------------------------------------
 
 /// A collection of all the local exception files we are using.
@@ -69,45 +385,240 @@ struct ExceptionSet {
     /// If the set gets dropped, so does the arc and the thread can check on
     /// it via a weak reference to it.
     alive: Arc<()>,
+
+    /// The metrics for the various files, in the same order as `files`.
+    metrics: Arc<Vec<Arc<FileMetrics>>>,
+
+    /// The number of cross-file conflicts found on the last validation.
+    conflict_count: Arc<AtomicU64>,
+}
+
+
+//------------ FileMetrics ----------------------------------------------------
+
+/// The metrics collected for a single configured SLURM file.
+#[derive(Default)]
+struct FileMetrics {
+    /// The time of the last successful load, if any.
+    last_success: Mutex<Option<SystemTime>>,
+
+    /// The number of assertions in the currently loaded content.
+    assertion_count: AtomicUsize,
+
+    /// The number of filters (prefix + bgpsec) in the currently loaded
+    /// content.
+    filter_count: AtomicUsize,
+
+    /// The total number of payloads dropped by filters so far.
+    dropped_count: AtomicU64,
+
+    /// The total number of payloads inserted by assertions so far.
+    inserted_count: AtomicU64,
+
+    /// The total number of failed load attempts so far.
+    error_count: AtomicU64,
+
+    /// The kind of the most recent load error, if any.
+    last_error_kind: Mutex<Option<io::ErrorKind>>,
+}
+
+impl FileMetrics {
+    /// Records a successful load of `content`.
+    fn record_success(&self, content: &Content) {
+        *self.last_success.lock().unwrap() = Some(SystemTime::now());
+        self.assertion_count.store(
+            content.assertions.len(), Ordering::Relaxed
+        );
+        self.filter_count.store(
+            content.filters.prefix.len() + content.filters.bgpsec.len(),
+            Ordering::Relaxed
+        );
+    }
+
+    /// Records a failed load attempt.
+    fn record_error(&self, err: &io::Error) {
+        self.error_count.fetch_add(1, Ordering::Relaxed);
+        *self.last_error_kind.lock().unwrap() = Some(err.kind());
+    }
+}
+
+
+//------------ Conflict --------------------------------------------------------
+
+/// A conflict detected between the exceptions declared in two files.
+///
+/// Indices refer to positions in the unit's configured `files` list.
+#[derive(Debug, PartialEq, Eq)]
+enum Conflict {
+    /// An assertion in `asserting` is dropped again by a filter in
+    /// `filtering`, so it never actually takes effect.
+    AssertionFilteredByLaterFile { asserting: usize, filtering: usize },
+
+    /// The exact same assertion is declared in both `first` and `second`.
+    DuplicateAssertion { first: usize, second: usize },
+}
+
+impl Conflict {
+    /// The index of the later file, i.e. the one `strict` mode rejects.
+    fn later_index(&self) -> usize {
+        match *self {
+            Conflict::AssertionFilteredByLaterFile { filtering, .. } => {
+                filtering
+            }
+            Conflict::DuplicateAssertion { second, .. } => second,
+        }
+    }
+
+    /// Describes the conflict using the given file labels.
+    fn describe(&self, labels: &[String]) -> String {
+        match *self {
+            Conflict::AssertionFilteredByLaterFile { asserting, filtering } => {
+                format!(
+                    "an assertion in '{}' is dropped again by a filter \
+                     in '{}'",
+                    labels[asserting], labels[filtering]
+                )
+            }
+            Conflict::DuplicateAssertion { first, second } => {
+                format!(
+                    "the same assertion is declared in both '{}' and '{}'",
+                    labels[first], labels[second]
+                )
+            }
+        }
+    }
+}
+
+
+//------------ Event -----------------------------------------------------------
+
+/// An event waking up the update thread.
+enum Event {
+    /// A filesystem watcher observed a change to the file at this index.
+    Changed(usize),
+
+    /// A SIGHUP was received; all files should be reloaded unconditionally.
+    Reload,
+}
+
+
+//------------ FileState ------------------------------------------------------
+
+/// The bookkeeping kept per file to detect whether it has changed.
+///
+/// Local files are tracked via their mtime; remote files are tracked via
+/// the `Last-Modified` and `ETag` response headers of the last successful
+/// fetch. A given [`FileConfig`] only ever uses the fields relevant to it.
+#[derive(Clone, Default)]
+struct FileState {
+    /// The modification time of a local file as of the last reload.
+    modified: Option<SystemTime>,
+
+    /// The `Last-Modified` header of a remote file as of the last fetch.
+    last_modified: Option<String>,
+
+    /// The `ETag` header of a remote file as of the last fetch.
+    etag: Option<String>,
 }
 
 impl ExceptionSet {
-    fn new(files: Vec<PathBuf>) -> Self {
+    fn new(
+        files: Vec<FileConfig>, state_path: Option<PathBuf>, strict: bool
+    ) -> Self {
+        let persisted = state_path.as_deref().and_then(|path| {
+            Self::load_persisted_state(path, files.len())
+        });
+
         // Doing things in this order avoids the need for type annotations.
         let res = ExceptionSet {
-            files: Arc::new(
+            files: Arc::new(match &persisted {
+                Some(persisted) => {
+                    persisted.iter().map(|content| {
+                        ArcSwap::new(Arc::new(content.clone()))
+                    }).collect()
+                }
+                None => {
+                    files.iter().map(|_| Default::default()).collect()
+                }
+            }),
+            alive: Arc::new(()),
+            metrics: Arc::new(
                 files.iter().map(|_| Default::default()).collect()
             ),
-            alive: Arc::new(()),
+            conflict_count: Arc::new(AtomicU64::new(0)),
         };
+
+        // If we started from a persisted snapshot, the metrics should
+        // reflect that content right away rather than looking empty until
+        // the next real file change.
+        if let Some(persisted) = persisted {
+            for (metrics, content) in res.metrics.iter().zip(persisted.iter())
+            {
+                metrics.record_success(content);
+            }
+        }
+
         let content = res.files.clone();
+        let metrics = res.metrics.clone();
+        let conflict_count = res.conflict_count.clone();
         let alive = Arc::downgrade(&res.alive);
 
         thread::spawn(move || {
-            Self::update_thread(files, content, alive)
+            Self::update_thread(
+                files, content, metrics, conflict_count, alive, state_path,
+                strict
+            )
         });
 
         res
     }
 
+    /// Returns the per-file metrics, in the same order as the files.
+    fn metrics(&self) -> Arc<Vec<Arc<FileMetrics>>> {
+        self.metrics.clone()
+    }
+
+    /// Returns the shared cross-file conflict counter.
+    fn conflict_count(&self) -> Arc<AtomicU64> {
+        self.conflict_count.clone()
+    }
+
     fn apply(&self, update: payload::Update) -> payload::Update {
         let serial = update.serial();
         let mut set = update.into_set();
 
-        for file in self.files.iter() {
-            set = file.load().apply(set);
-            
+        for (file, metrics) in self.files.iter().zip(self.metrics.iter()) {
+            set = file.load().apply(set, metrics);
         }
 
         payload::Update::new(serial, set, None)
     }
 
+    /// Runs the update thread.
+    ///
+    /// The thread is primarily event-driven: it watches the parent
+    /// directory of every local file for changes and otherwise blocks,
+    /// only waking up to re-check the specific file a watch event was
+    /// about. A SIGHUP forces an immediate, unconditional re-read of all
+    /// files. A slow periodic sweep covers remote files -- which have no
+    /// filesystem events of their own -- and local files whose atomic
+    /// rename/replace confused the watcher.
     fn update_thread(
-        paths: Vec<PathBuf>,
+        files: Vec<FileConfig>,
         content: Arc<Vec<ArcSwap<Content>>>,
+        metrics: Arc<Vec<Arc<FileMetrics>>>,
+        conflict_count: Arc<AtomicU64>,
         alive: Weak<()>,
+        state_path: Option<PathBuf>,
+        strict: bool,
     ) {
-        let mut modified = vec![None::<SystemTime>; paths.len()];
+        let mut state: Vec<_> =
+            files.iter().map(|_| FileState::default()).collect();
+        let labels: Vec<String> = files.iter().map(FileConfig::label).collect();
+
+        let (tx, rx) = mpsc::channel();
+        let _watcher = Self::spawn_watcher(&files, tx.clone());
+        Self::spawn_signal_thread(tx);
 
         loop {
             if alive.upgrade().is_none() {
@@ -115,59 +626,382 @@ impl ExceptionSet {
                 return
             }
 
-            for (path, (modified, content)) in
-                paths.iter().zip(modified.iter_mut().zip(content.iter()))
-            {
-                // We simply ignore any errors for now.
-                let _ = Self::update_file(path, modified, content);
+            // A specific file changed: only that one needs re-checking,
+            // but not forcibly -- its own mtime/ETag check still applies
+            // in case the event was spurious. A SIGHUP or a fallback sweep
+            // re-checks every file; only SIGHUP forces an unconditional
+            // reload regardless of mtime.
+            let (targets, force): (Vec<usize>, bool) =
+                match rx.recv_timeout(FALLBACK_SLEEP) {
+                    Ok(Event::Changed(index)) => (vec![index], false),
+                    Ok(Event::Reload) => ((0..files.len()).collect(), true),
+                    Err(RecvTimeoutError::Timeout) => {
+                        ((0..files.len()).collect(), false)
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+
+            let previous: Vec<_> =
+                content.iter().map(|item| item.load_full()).collect();
+
+            let mut changed = false;
+            for &index in &targets {
+                if Self::reload_one(
+                    &labels[index], &files[index], &mut state[index],
+                    &content[index], &metrics[index], force
+                ) {
+                    changed = true;
+                }
+            }
+
+            if changed {
+                Self::validate_and_enforce(
+                    &labels, &content, &metrics, &previous, &targets, strict,
+                    &conflict_count,
+                );
+            }
+
+            // Only ever persist a snapshot that was built from files which
+            // all parsed cleanly, so a corrupt file can never poison it.
+            if changed {
+                if let Some(state_path) = state_path.as_deref() {
+                    Self::persist_state(state_path, &content);
+                }
+            }
+        }
+    }
+
+    /// Checks the current content of all files for cross-file conflicts,
+    /// logging them and reporting their number via `conflict_count`.
+    ///
+    /// When `strict` is set, any of the just-updated `targets` that
+    /// conflicts with an earlier file is reverted to its `previous`
+    /// content instead of being applied.
+    fn validate_and_enforce(
+        labels: &[String],
+        content: &Arc<Vec<ArcSwap<Content>>>,
+        metrics: &[Arc<FileMetrics>],
+        previous: &[Arc<Content>],
+        targets: &[usize],
+        strict: bool,
+        conflict_count: &AtomicU64,
+    ) {
+        let snapshot: Vec<_> =
+            content.iter().map(|item| item.load_full()).collect();
+        let conflicts = Self::find_conflicts(&snapshot);
+        conflict_count.store(conflicts.len() as u64, Ordering::Relaxed);
+
+        for conflict in &conflicts {
+            log::warn!(
+                "local exceptions: {}", conflict.describe(labels)
+            );
+        }
+
+        if strict {
+            for &index in targets {
+                let rejected = conflicts.iter().any(|conflict| {
+                    conflict.later_index() == index
+                });
+                if rejected {
+                    log::warn!(
+                        "local exceptions: refusing to apply '{}', \
+                         it conflicts with an earlier file",
+                        labels[index]
+                    );
+                    content[index].store(previous[index].clone());
+                    metrics[index].record_success(&previous[index]);
+                }
+            }
+        }
+    }
+
+    /// Finds conflicts between the currently loaded content of all files.
+    ///
+    /// Two kinds of conflicts are detected: an assertion in an earlier
+    /// file that a later file's filters drop again, and the exact same
+    /// assertion declared in two different files.
+    fn find_conflicts(content: &[Arc<Content>]) -> Vec<Conflict> {
+        let mut conflicts = Vec::new();
+        for i in 0..content.len() {
+            for j in (i + 1)..content.len() {
+                for payload in content[i].assertions.iter_payload() {
+                    if content[j].filters.drop_payload(&payload) {
+                        conflicts.push(Conflict::AssertionFilteredByLaterFile {
+                            asserting: i, filtering: j,
+                        });
+                    }
+                    if content[j].assertions.iter_payload().any(|other| {
+                        other == payload
+                    }) {
+                        conflicts.push(Conflict::DuplicateAssertion {
+                            first: i, second: j,
+                        });
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Reloads a single file and records the outcome in its metrics.
+    ///
+    /// Returns whether the file's content changed.
+    fn reload_one(
+        label: &str,
+        file: &FileConfig,
+        state: &mut FileState,
+        content: &ArcSwap<Content>,
+        metrics: &FileMetrics,
+        force: bool,
+    ) -> bool {
+        match Self::update_file(file, state, content, force) {
+            Ok(true) => {
+                metrics.record_success(&content.load());
+                true
+            }
+            Ok(false) => false,
+            // We log the error and record it in the metrics, then keep
+            // serving the last good content.
+            Err(err) => {
+                log::warn!(
+                    "local exceptions: failed to load '{}': {}", label, err
+                );
+                metrics.record_error(&err);
+                false
+            }
+        }
+    }
+
+    /// Registers a filesystem watcher on the parent directory of every
+    /// local file, forwarding `Event::Changed` for the files it matches.
+    ///
+    /// The watcher is returned so the caller keeps it alive for as long as
+    /// the update thread runs; dropping it stops the notifications.
+    fn spawn_watcher(
+        files: &[FileConfig], tx: mpsc::Sender<Event>
+    ) -> Option<RecommendedWatcher> {
+        let watched: Vec<(usize, PathBuf)> = files.iter().enumerate()
+            .filter_map(|(index, file)| match file {
+                FileConfig::Remote { .. } => None,
+                _ => Some((index, file.path().to_owned())),
+            })
+            .collect();
+        if watched.is_empty() {
+            return None
+        }
+
+        let mut watcher = match notify::recommended_watcher(
+            move |res: notify::Result<notify::Event>| {
+                let event = match res {
+                    Ok(event) => event,
+                    Err(_) => return,
+                };
+                for changed in &event.paths {
+                    for (index, path) in &watched {
+                        if changed == path {
+                            let _ = tx.send(Event::Changed(*index));
+                        }
+                    }
+                }
             }
+        ) {
+            Ok(watcher) => watcher,
+            Err(_) => return None,
+        };
 
-            thread::sleep(UPDATE_SLEEP);
+        for file in files {
+            if let FileConfig::Remote { .. } = file {
+                continue
+            }
+            if let Some(parent) = file.path().parent() {
+                let _ = watcher.watch(parent, RecursiveMode::NonRecursive);
+            }
         }
+
+        Some(watcher)
+    }
+
+    /// Spawns a thread that turns a SIGHUP into an `Event::Reload`.
+    fn spawn_signal_thread(tx: mpsc::Sender<Event>) {
+        let mut signals = match Signals::new([SIGHUP]) {
+            Ok(signals) => signals,
+            Err(_) => return,
+        };
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                if tx.send(Event::Reload).is_err() {
+                    return
+                }
+            }
+        });
     }
 
     fn update_file(
-        path: &Path,
-        old_modified: &mut Option<SystemTime>,
-        content: &ArcSwap<Content>
-    ) -> Result<(), io::Error> {
+        file: &FileConfig,
+        state: &mut FileState,
+        content: &ArcSwap<Content>,
+        force: bool,
+    ) -> Result<bool, io::Error> {
+        match file {
+            FileConfig::Remote { url } => {
+                Self::update_remote_file(url, state, content, force)
+            }
+            FileConfig::Plain(_) | FileConfig::Encrypted { .. } => {
+                Self::update_local_file(file, state, content, force)
+            }
+        }
+    }
+
+    fn update_local_file(
+        file: &FileConfig,
+        state: &mut FileState,
+        content: &ArcSwap<Content>,
+        force: bool,
+    ) -> Result<bool, io::Error> {
+        let path = file.path();
         let new_modified = fs::metadata(path)?.modified()?;
-        if let Some(old_modified) = old_modified.as_ref() {
-            if new_modified >= *old_modified {
-                return Ok(())
+        if !force {
+            if let Some(old_modified) = state.modified {
+                if new_modified <= old_modified {
+                    return Ok(false)
+                }
             }
         }
 
-        content.store(Arc::new(
-            SlurmFile::from_reader(
-                io::BufReader::new(
-                    fs::File::open(path)?
-                )
-            )?.into()
-        ));
-        *old_modified = Some(new_modified);
-        Ok(())
+        let reader = io::BufReader::new(fs::File::open(path)?);
+        let slurm = match file.encryption() {
+            Some(encryption) => SlurmFile::from_reader(
+                DecryptReader::new(reader, encryption)?
+            )?,
+            None => SlurmFile::from_reader(reader)?,
+        };
+        content.store(Arc::new(slurm.into()));
+        state.modified = Some(new_modified);
+        Ok(true)
+    }
+
+    /// Fetches a remote SLURM file, using conditional requests.
+    ///
+    /// A `304 Not Modified` response is the remote equivalent of the local
+    /// "mtime unchanged" case and results in no update. When `force` is
+    /// set, the conditional headers are omitted so a fresh copy is always
+    /// fetched and applied.
+    fn update_remote_file(
+        url: &str,
+        state: &mut FileState,
+        content: &ArcSwap<Content>,
+        force: bool,
+    ) -> Result<bool, io::Error> {
+        let mut req = ureq::get(url).timeout(REMOTE_TIMEOUT);
+        if !force {
+            if let Some(last_modified) = state.last_modified.as_deref() {
+                req = req.set("If-Modified-Since", last_modified);
+            }
+            if let Some(etag) = state.etag.as_deref() {
+                req = req.set("If-None-Match", etag);
+            }
+        }
+
+        let res = match req.call() {
+            Ok(res) => res,
+            Err(ureq::Error::Status(304, _)) => return Ok(false),
+            Err(err) => {
+                return Err(io::Error::new(io::ErrorKind::Other, err))
+            }
+        };
+
+        let last_modified = res.header("Last-Modified").map(String::from);
+        let etag = res.header("ETag").map(String::from);
+        let slurm = SlurmFile::from_reader(res.into_reader())?;
+        content.store(Arc::new(slurm.into()));
+        state.last_modified = last_modified;
+        state.etag = etag;
+        Ok(true)
     }
+
+    /// Loads a previously persisted snapshot, if one is present and valid.
+    ///
+    /// Returns `None` if there is no state file, it can’t be parsed, it is
+    /// of an unsupported version, or its file count doesn’t match `len` —
+    /// in all these cases we simply fall back to the empty default and let
+    /// the regular file reads populate things.
+    fn load_persisted_state(
+        path: &Path, len: usize
+    ) -> Option<Vec<Content>> {
+        let data = fs::read(path).ok()?;
+        let state: PersistedState = serde_json::from_slice(&data).ok()?;
+        if state.version != STATE_FILE_VERSION {
+            return None
+        }
+        if state.files.len() != len {
+            return None
+        }
+        Some(state.files)
+    }
+
+    /// Persists the current content of all files to the state file.
+    ///
+    /// This is best-effort: if writing fails, we simply keep going and try
+    /// again on the next successful reload.
+    fn persist_state(path: &Path, content: &[ArcSwap<Content>]) {
+        let state = PersistedState {
+            version: STATE_FILE_VERSION,
+            files: content.iter().map(|item| (**item.load()).clone()).collect(),
+        };
+        let data = match serde_json::to_vec(&state) {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+        let tmp_path = path.with_extension("tmp");
+        if fs::write(&tmp_path, &data).is_ok() {
+            let _ = fs::rename(&tmp_path, path);
+        }
+    }
+}
+
+
+//------------ PersistedState -------------------------------------------------
+
+/// The version of the on-disk format used by [`ExceptionSet::persist_state`].
+const STATE_FILE_VERSION: u8 = 1;
+
+/// The on-disk snapshot of an [`ExceptionSet`], used for a warm start.
+#[derive(Deserialize, Serialize)]
+struct PersistedState {
+    /// The format version, to allow for future migrations.
+    version: u8,
+
+    /// The content of each configured file, in configuration order.
+    files: Vec<Content>,
 }
 
 
 //------------ Content -------------------------------------------------------
 
 /// The content of a SLURM file in slightly pre-processed form.
-#[derive(Default)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 struct Content {
     filters: ValidationOutputFilters,
     assertions: payload::Pack,
 }
 
 impl Content {
-    fn apply(&self, set: payload::Set) -> payload::Set {
+    fn apply(&self, set: payload::Set, metrics: &FileMetrics) -> payload::Set {
+        let before = set.len();
+
         // First filters, then assertions.
         let filtered = set.filter(|payload| {
             !self.filters.drop_payload(payload)
         });
+        metrics.dropped_count.fetch_add(
+            (before - filtered.len()) as u64, Ordering::Relaxed
+        );
+
         let mut builder = filtered.to_builder();
         builder.insert_pack(self.assertions.clone());
+        metrics.inserted_count.fetch_add(
+            self.assertions.len() as u64, Ordering::Relaxed
+        );
         builder.finalize()
     }
 }
@@ -247,7 +1081,145 @@ mod test {
             assertions: p3
         };
 
-        assert_eq!(content.apply(input), output);
+        let metrics = FileMetrics::default();
+        assert_eq!(content.apply(input, &metrics), output);
+    }
+
+    /// Builds a `Content` asserting `payloads` and filtering nothing.
+    fn asserting(payloads: &[Payload]) -> Content {
+        let mut builder = payload::PackBuilder::empty();
+        for payload in payloads {
+            builder.insert_unchecked(payload.clone());
+        }
+        Content {
+            filters: ValidationOutputFilters {
+                prefix: Vec::new(), bgpsec: Vec::new()
+            },
+            assertions: builder.finalize(),
+        }
+    }
+
+    /// Builds a `Content` filtering out `payload` and asserting nothing.
+    fn filtering(payload: &Payload) -> Content {
+        let prefix = match payload {
+            Payload::Origin(origin) => vec![PrefixFilter::new(
+                Some(origin.prefix.prefix()), Some(origin.asn), None
+            )],
+            _ => Vec::new(),
+        };
+        Content {
+            filters: ValidationOutputFilters { prefix, bgpsec: Vec::new() },
+            assertions: payload::PackBuilder::empty().finalize(),
+        }
+    }
+
+    #[test]
+    fn find_conflicts_none() {
+        let p1 = testrig::p(1);
+        let p2 = testrig::p(2);
+        let content = vec![
+            Arc::new(asserting(&[p1])), Arc::new(asserting(&[p2])),
+        ];
+        assert_eq!(ExceptionSet::find_conflicts(&content), Vec::new());
+    }
+
+    #[test]
+    fn find_conflicts_assertion_filtered_by_later_file() {
+        let p1 = testrig::p(1);
+        let content = vec![
+            Arc::new(asserting(&[p1.clone()])), Arc::new(filtering(&p1)),
+        ];
+        assert_eq!(
+            ExceptionSet::find_conflicts(&content),
+            vec![Conflict::AssertionFilteredByLaterFile {
+                asserting: 0, filtering: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn find_conflicts_duplicate_assertion() {
+        let p1 = testrig::p(1);
+        let content = vec![
+            Arc::new(asserting(&[p1.clone()])), Arc::new(asserting(&[p1])),
+        ];
+        assert_eq!(
+            ExceptionSet::find_conflicts(&content),
+            vec![Conflict::DuplicateAssertion { first: 0, second: 1 }]
+        );
+    }
+
+    #[test]
+    fn validate_and_enforce_reverts_in_strict_mode() {
+        let p1 = testrig::p(1);
+        let labels = vec!["a".to_string(), "b".to_string()];
+        let previous = vec![
+            Arc::new(asserting(&[p1.clone()])), Arc::new(Content::default()),
+        ];
+        let content = Arc::new(vec![
+            ArcSwap::new(previous[0].clone()),
+            ArcSwap::new(Arc::new(filtering(&p1))),
+        ]);
+        let metrics = vec![
+            Arc::new(FileMetrics::default()), Arc::new(FileMetrics::default()),
+        ];
+        let conflict_count = AtomicU64::new(0);
+
+        ExceptionSet::validate_and_enforce(
+            &labels, &content, &metrics, &previous, &[1], true,
+            &conflict_count,
+        );
+
+        assert_eq!(conflict_count.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            content[1].load().assertions.len(), previous[1].assertions.len()
+        );
+        assert_eq!(
+            content[1].load().filters.prefix.len(),
+            previous[1].filters.prefix.len()
+        );
+    }
+
+    #[test]
+    fn decrypt_reader_round_trip() {
+        let config = EncryptionConfig { key: [7u8; 32] };
+        let nonce = [9u8; NONCE_LEN];
+        let plaintext = b"hello SLURM world, this is a test payload!";
+
+        // A stream cipher's keystream only depends on key and nonce, so
+        // running the plaintext through it once produces the ciphertext.
+        // The nonce itself travels as a plaintext header in front of it.
+        let mut cipher = ChaCha20::new(&config.key.into(), &nonce.into());
+        let mut ciphertext = plaintext.to_vec();
+        cipher.apply_keystream(&mut ciphertext);
+        assert_ne!(ciphertext, plaintext.to_vec());
+
+        let mut file = nonce.to_vec();
+        file.extend_from_slice(&ciphertext);
+
+        let mut reader = DecryptReader::new(&file[..], &config).unwrap();
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+
+        assert_eq!(decrypted, plaintext.to_vec());
+    }
+
+    #[test]
+    fn decrypt_reader_reusing_a_key_with_different_nonces_differs() {
+        let config = EncryptionConfig { key: [3u8; 32] };
+        let plaintext = b"some SLURM content";
+
+        let encrypt = |nonce: [u8; NONCE_LEN]| {
+            let mut cipher = ChaCha20::new(&config.key.into(), &nonce.into());
+            let mut ciphertext = plaintext.to_vec();
+            cipher.apply_keystream(&mut ciphertext);
+            ciphertext
+        };
+
+        // Re-encrypting the same plaintext under the same key but a
+        // different nonce, as a re-encryption is expected to do, must not
+        // reuse the same keystream.
+        assert_ne!(encrypt([1u8; NONCE_LEN]), encrypt([2u8; NONCE_LEN]));
     }
 }
 